@@ -0,0 +1,20 @@
+//! Support types for the [`Selectable`](macro@Selectable) derive.
+//!
+//! The derive itself lives in the `diesel-selectable-macro` crate, since a
+//! `proc-macro = true` crate cannot export anything besides macros; this
+//! crate re-exports it and hosts the trait its expansion depends on.
+
+pub use diesel_selectable_macro::Selectable;
+
+/// A helper trait implemented by every `#[derive(Selectable)]` struct,
+/// giving its `fields()` tuple a name that other derives can refer to.
+///
+/// This exists so that a struct can embed another `Selectable` struct via
+/// `#[diesel(embed)]`: the parent needs a type to put in its own
+/// `fields()`/`select()` signatures for the embedded struct's (possibly
+/// nested) tuple of columns, and `Self::Fields` is that type.
+pub trait SelectableFields {
+  /// The tuple of columns (or, for embedded fields, nested tuples of
+  /// columns) that this struct selects.
+  type Fields;
+}
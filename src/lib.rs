@@ -2,22 +2,18 @@
 //! that performs a Diesel query by key names, rather than position (as
 //! [`diesel::Queryable`] does).
 
-use std::ops::Deref;
-
 use darling::ast;
 use darling::FromDeriveInput;
 use darling::FromField;
 use proc_macro::TokenStream as TokenStream1;
 use proc_macro2::TokenStream;
 use quote::quote;
-use quote::ToTokens;
-use syn::parse::Parse;
-use syn::parse::ParseStream;
 use syn::parse_macro_input;
-use syn::Attribute;
 use syn::DeriveInput;
+use syn::Expr;
 use syn::Generics;
 use syn::Ident;
+use syn::Type;
 
 /// Provide a `.select()` function based on the struct's fields.
 #[proc_macro_derive(Selectable, attributes(diesel))]
@@ -26,15 +22,20 @@ pub fn derive_selectable(input: TokenStream1) -> TokenStream1 {
   SelectableStruct::from_derive_input(&parse_macro_input!(
     input as DeriveInput
   ))
-  .map(|recv| quote!(#recv))
+  .and_then(|recv| recv.expand())
   .unwrap_or_else(|err| err.write_errors())
   .into()
 }
 
 /// Struct that receives the input struct to `Selectable` and augments it
 /// with the `.select()` function.
+///
+/// Struct-level `#[diesel(...)]` options are captured directly as fields
+/// here (via darling), rather than re-parsed by hand, so this is also
+/// the place to add support for future options such as
+/// `treat_none_as_null`.
 #[derive(FromDeriveInput)]
-#[darling(supports(struct_named), forward_attrs(diesel))]
+#[darling(supports(struct_named), attributes(diesel))]
 pub(crate) struct SelectableStruct {
   /// The name of the struct.
   ident: Ident,
@@ -45,29 +46,16 @@ pub(crate) struct SelectableStruct {
   /// Data on the individual fields.
   data: ast::Data<(), SelectableField>,
 
-  /// Attributes on the overall struct.
-  attrs: Vec<Attribute>,
+  /// The name of the table this struct selects from, set via
+  /// `#[diesel(table_name = ...)]`.
+  table_name: Ident,
 }
 
 impl SelectableStruct {
-  /// Return the field identifiers on the struct.
-  #[cfg(not(tarpaulin_include))]
-  fn field_names(&self) -> Vec<&Ident> {
-    self
-      .data
-      .as_ref()
-      .take_struct()
-      .expect("Selectable only supports named structs")
-      .into_iter()
-      .map(|field| field.name())
-      .collect()
-  }
-}
-
-impl ToTokens for SelectableStruct {
-  /// Return an automatically generated `Selectable` implementation.
+  /// Build the `Selectable` implementation for this struct, or a spanned
+  /// error pointing at whatever the derive needs but didn't find.
   #[cfg(not(tarpaulin_include))]
-  fn to_tokens(&self, tokens: &mut TokenStream) {
+  fn expand(&self) -> darling::Result<TokenStream> {
     // Put together our basic tokens: the struct identifier, and any generics
     // (types, lifetimes, etc.) we need to carry forward to the `Selectable`
     // implementation.
@@ -76,45 +64,50 @@ impl ToTokens for SelectableStruct {
     let (impl_generics, type_generics, where_clause) =
       self.generics.split_for_impl();
 
-    // Get the name of the table.
-    let diesel = self
-      .attrs
-      .iter()
-      .find(|attr| attr.path.is_ident("diesel"))
-      .expect("The `diesel` attribute is required");
-    let args = syn::parse_macro_input::parse::<CommaSeparatedArguments>(
-      diesel.into_token_stream().into(),
-    )
-    .expect("Unable to parse arguments.");
-    let table_name = args
-      .iter()
-      .find_map(|arg| {
-        syn::parse_macro_input::parse::<TableNameParser>(arg.clone().into())
-          .ok()
-      })
-      .expect("No `table_name` argument found.")
-      .table_name;
+    let table_name = &self.table_name;
     let table = quote! { crate::schema::#table_name::dsl::#table_name };
 
-    // Get the list of fields as a tuple.
-    let fields: Vec<TokenStream> = self
-      .field_names()
-      .iter()
-      .map(|f| quote! { crate::schema::#table_name::dsl::#f })
-      .collect();
+    // Get the list of fields, as the expressions that select them and the
+    // types those expressions have. These are the same tokens for a plain
+    // column (a Diesel column path is both a type and a value), but differ
+    // for an embedded field, which selects via a nested `fields()` call and
+    // types via that struct's own `Fields`, and for a `select_expression`
+    // field, which types via its `select_expression_type`.
+    // `#[darling(supports(struct_named))]` above already rejects anything
+    // else before `expand` is ever called, so this is always the struct
+    // variant.
+    let struct_fields = self
+      .data
+      .as_ref()
+      .take_struct()
+      .expect("SelectableStruct only supports named structs");
+    let struct_fields =
+      struct_fields.iter().filter(|field| !field.skip);
+    let values = struct_fields
+      .clone()
+      .map(|field| field.value(table_name))
+      .collect::<darling::Result<Vec<TokenStream>>>()?;
+    let types = struct_fields
+      .map(|field| field.field_type(table_name))
+      .collect::<darling::Result<Vec<TokenStream>>>()?;
 
     // Add the select implementation.
-    tokens.extend(quote! {
+    Ok(quote! {
+      #[automatically_derived]
+      impl #impl_generics ::diesel_selectable::SelectableFields for #ident #type_generics #where_clause {
+        type Fields = (#(#types),*);
+      }
+
       #[automatically_derived]
       impl #impl_generics #ident #type_generics #where_clause {
         /// Return a tuple of the table's fields.
-        pub fn fields() -> (#(#fields),*) {
-          (#(#fields),*)
+        pub fn fields() -> <Self as ::diesel_selectable::SelectableFields>::Fields {
+          (#(#values),*)
         }
 
         /// Construct a query object to retrieve objects from the corresponding
         /// database table.
-        pub fn select() -> diesel::dsl::Select<#table, (#(#fields),*)> {
+        pub fn select() -> diesel::dsl::Select<#table, <Self as ::diesel_selectable::SelectableFields>::Fields> {
           #table.select(Self::fields())
         }
       }
@@ -122,71 +115,140 @@ impl ToTokens for SelectableStruct {
   }
 }
 
+/// A `syn::Type` parsed from a quoted string attribute value, e.g.
+/// `#[diesel(select_expression_type = "diesel::sql_types::BigInt")]`.
+///
+/// This must be a string rather than a bare type path: real Diesel
+/// expression types are routinely generic (`SqlLiteral<Text>`,
+/// `Nullable<Integer>`, ...), and `Foo<Bar>` is ambiguous as a bare
+/// attribute value — syn parses an unquoted `key = value` pair as an
+/// expression, and a lone `<`/`>` pair without turbofish reads as a
+/// (disallowed) chained comparison, so the attribute fails to parse at
+/// all before any of this crate's code runs. Quoting sidesteps that
+/// entirely, since the type text then lives inside a string literal
+/// rather than in the attribute's own token tree.
+struct SelectExpressionType(Type);
+
+impl darling::FromMeta for SelectExpressionType {
+  fn from_string(value: &str) -> darling::Result<Self> {
+    syn::parse_str(value)
+      .map(Self)
+      .map_err(|err| darling::Error::custom(err.to_string()))
+  }
+
+  fn from_expr(expr: &Expr) -> darling::Result<Self> {
+    match expr {
+      Expr::Lit(lit) => darling::FromMeta::from_value(&lit.lit),
+      Expr::Group(group) => Self::from_expr(&group.expr),
+      _ => Err(
+        darling::Error::custom(
+          "`select_expression_type` must be a quoted string, e.g. `select_expression_type = \"diesel::sql_types::BigInt\"`",
+        )
+        .with_span(expr),
+      ),
+    }
+  }
+}
+
 /// A representation of a single field on the struct.
 #[derive(FromField)]
-#[darling(attributes(field_names))]
+#[darling(attributes(diesel))]
 struct SelectableField {
   /// The name of the field, or None for tuple fields.
   ident: Option<Ident>,
+
+  /// An override for the column this field corresponds to, set via
+  /// `#[diesel(column_name = ...)]`. Falls back to the field's own
+  /// identifier when absent.
+  column_name: Option<Ident>,
+
+  /// The field's type, used to splice an embedded struct's fields into
+  /// this one's.
+  ty: Type,
+
+  /// Whether this field holds another `Selectable` struct whose columns
+  /// should be flattened into this one's, set via `#[diesel(embed)]`.
+  #[darling(default)]
+  embed: bool,
+
+  /// Whether this field should be omitted from `fields()` and
+  /// `select()` entirely, set via `#[diesel(skip)]`. Useful for fields
+  /// that are populated outside the query, such as computed values.
+  #[darling(default)]
+  skip: bool,
+
+  /// An arbitrary SQL expression to select this field with, in place of
+  /// a plain column, set via `#[diesel(select_expression = ...)]`.
+  /// Requires `select_expression_type` alongside it.
+  select_expression: Option<Expr>,
+
+  /// The type of `select_expression`, set via
+  /// `#[diesel(select_expression_type = "...")]` as a quoted string
+  /// (required, even for non-generic types — see `SelectExpressionType`),
+  /// e.g. `#[diesel(select_expression_type = "diesel::sql_types::BigInt")]`.
+  select_expression_type: Option<SelectExpressionType>,
 }
 
 impl SelectableField {
-  /// Return the field's identifier, or panic if there is no identifier.
+  /// Return the field's identifier.
+  ///
+  /// `SelectableStruct` only derives for `struct_named` shapes, so every
+  /// field reaching this point came from a named struct and always has
+  /// one.
   #[cfg(not(tarpaulin_include))]
   fn name(&self) -> &Ident {
-    self.ident.as_ref().expect("Selectable only supports named fields")
+    self
+      .ident
+      .as_ref()
+      .expect("SelectableStruct only supports named fields")
   }
-}
 
-struct CommaSeparatedArguments(Vec<TokenStream>);
-
-impl Parse for CommaSeparatedArguments {
-  fn parse(input: ParseStream) -> syn::Result<Self> {
-    let bracketed;
-    let content;
-    input.parse::<syn::Token![#]>()?;
-    syn::bracketed!(bracketed in input);
-    bracketed.parse::<syn::Ident>()?;
-    syn::parenthesized!(content in bracketed);
-
-    // There are zero or more arguments, comma separated. Split them up.
-    Ok(Self(
-      content
-        .parse_terminated::<TokenStream, syn::Token![,]>(TokenStream::parse)
-        .expect("Failed to parse comma-separated args")
-        .into_iter()
-        .collect(),
-    ))
+  /// Return the column this field corresponds to: the `column_name`
+  /// override if one was provided, or the field's own identifier
+  /// otherwise.
+  #[cfg(not(tarpaulin_include))]
+  fn column(&self) -> &Ident {
+    self.column_name.as_ref().unwrap_or_else(|| self.name())
   }
-}
-
-impl Deref for CommaSeparatedArguments {
-  type Target = Vec<TokenStream>;
 
-  fn deref(&self) -> &Self::Target {
-    &self.0
+  /// Return the expression that selects this field: a call to an
+  /// embedded struct's own `fields()`, a `select_expression` override,
+  /// or the dsl column path, in that order of precedence.
+  #[cfg(not(tarpaulin_include))]
+  fn value(&self, table_name: &Ident) -> darling::Result<TokenStream> {
+    if self.embed {
+      let ty = &self.ty;
+      return Ok(quote! { <#ty>::fields() });
+    }
+    if let Some(expr) = &self.select_expression {
+      return Ok(quote! { #expr });
+    }
+    let column = self.column();
+    Ok(quote! { crate::schema::#table_name::dsl::#column })
   }
-}
 
-struct TableNameParser {
-  table_name: Ident,
-}
-
-impl Parse for TableNameParser {
-  fn parse(input: ParseStream) -> syn::Result<Self> {
-    // We're looking for `table_name = foo`, so first we can split on `=` and
-    // collect the result; the length should be 2 if this is a match.
-    let key_val: Vec<Ident> = input
-      .parse_terminated::<Ident, syn::Token![=]>(Ident::parse)
-      .expect("Not = separated.")
-      .into_iter()
-      .collect();
-    if key_val.len() != 2 {
-      return Err(input.error("Incorrect token length."));
+  /// Return the type of this field's contribution to the select tuple:
+  /// the dsl column path (which, like all Diesel columns, is a unit
+  /// type as well as a value), an embedded field's own
+  /// `diesel_selectable::SelectableFields::Fields`, or a
+  /// `select_expression_type` override.
+  #[cfg(not(tarpaulin_include))]
+  fn field_type(&self, table_name: &Ident) -> darling::Result<TokenStream> {
+    if self.embed {
+      let ty = &self.ty;
+      return Ok(quote! { <#ty as ::diesel_selectable::SelectableFields>::Fields });
     }
-    match key_val[0] == "table_name" {
-      true => Ok(Self { table_name: key_val[1].clone() }),
-      false => Err(input.error("Wrong attribute,")),
+    if let Some(expr) = &self.select_expression {
+      return match &self.select_expression_type {
+        Some(SelectExpressionType(ty)) => Ok(quote! { #ty }),
+        None => Err(darling::Error::custom(
+          "`select_expression` requires `select_expression_type`",
+        )
+        .with_span(expr)),
+      };
     }
+    let column = self.column();
+    Ok(quote! { crate::schema::#table_name::dsl::#column })
   }
 }
+
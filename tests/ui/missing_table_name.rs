@@ -0,0 +1,8 @@
+use diesel_selectable_macro::Selectable;
+
+#[derive(Selectable)]
+struct User {
+  id: i32,
+}
+
+fn main() {}
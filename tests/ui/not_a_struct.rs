@@ -0,0 +1,9 @@
+use diesel_selectable_macro::Selectable;
+
+#[derive(Selectable)]
+#[diesel(table_name = users)]
+enum NotAStruct {
+  A,
+}
+
+fn main() {}
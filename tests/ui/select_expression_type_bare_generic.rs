@@ -0,0 +1,13 @@
+use diesel_selectable_macro::Selectable;
+
+#[derive(Selectable)]
+#[diesel(table_name = users)]
+struct User {
+  #[diesel(
+    select_expression = crate::schema::users::dsl::id,
+    select_expression_type = SqlLiteral<diesel::sql_types::Text>
+  )]
+  computed: i32,
+}
+
+fn main() {}
@@ -0,0 +1,10 @@
+use diesel_selectable_macro::Selectable;
+
+#[derive(Selectable)]
+#[diesel(table_name = users)]
+struct User {
+  #[diesel(select_expression = crate::schema::users::dsl::id)]
+  computed: i32,
+}
+
+fn main() {}
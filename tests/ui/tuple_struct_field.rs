@@ -0,0 +1,7 @@
+use diesel_selectable_macro::Selectable;
+
+#[derive(Selectable)]
+#[diesel(table_name = users)]
+struct User(i32, String);
+
+fn main() {}